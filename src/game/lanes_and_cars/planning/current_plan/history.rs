@@ -0,0 +1,67 @@
+use std::rc::Rc;
+
+use super::{PlanStep, Settings};
+use super::intent::{Intent, apply_intent};
+use super::super::plan::BuiltStrokes;
+
+// Undo/redo is a plain index move over `snapshots`, no copying. `apply`
+// itself still pays `apply_intent`'s full `plan_delta` clone - sharing
+// unchanged strokes between versions would mean storing `new_strokes` as a
+// persistent map, which means changing `PlanDelta` itself, and that type
+// lives outside this snapshot.
+pub struct PlanHistory {
+    snapshots: Vec<Rc<PlanStep>>,
+    current: usize,
+    max_depth: usize,
+}
+
+impl PlanHistory {
+    pub fn new(initial: PlanStep, max_depth: usize) -> Self {
+        PlanHistory {
+            snapshots: vec![Rc::new(initial)],
+            current: 0,
+            max_depth: max_depth,
+        }
+    }
+
+    pub fn current(&self) -> &PlanStep {
+        &self.snapshots[self.current]
+    }
+
+    // applies `intent` and records the result as a new snapshot, discarding
+    // any redo branch beyond the current one
+    pub fn apply(&mut self,
+                intent: Intent,
+                maybe_still_built_strokes: Option<&BuiltStrokes>,
+                settings: &Settings) {
+        let new_step = apply_intent(self.current(), &intent, maybe_still_built_strokes, settings);
+
+        self.snapshots.truncate(self.current + 1);
+        self.snapshots.push(Rc::new(new_step));
+        self.current += 1;
+
+        if self.snapshots.len() > self.max_depth {
+            let overflow = self.snapshots.len() - self.max_depth;
+            self.snapshots.drain(0..overflow);
+            self.current -= overflow;
+        }
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if self.current > 0 {
+            self.current -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        if self.current + 1 < self.snapshots.len() {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
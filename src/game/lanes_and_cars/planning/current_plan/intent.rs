@@ -1,3 +1,5 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Ordering;
 use compact::{CVec, CDict};
 use descartes::{N, V2, P2, Segment, Norm, FiniteCurve, Curve, RelativeToBasis,
                 WithUniqueOrthogonal, RoughlyComparable, Dot};
@@ -5,16 +7,25 @@ use descartes::{N, V2, P2, Segment, Norm, FiniteCurve, Curve, RelativeToBasis,
 use super::{PlanStep, Settings, LaneStrokeRef, SelectableStrokeRef, ContinuationMode};
 use super::super::plan::{PlanDelta, BuiltStrokes};
 use super::super::lane_stroke::{LaneStroke, LaneStrokeNode};
-use itertools::Itertools;
+use super::super::spatial_index::{Aabb, RTree};
+
+// proximity radii used to turn exact geometric tests into R-tree queries,
+// kept identical to the literals those tests already compared against
+const SELECT_PARALLEL_PROXIMITY: N = 60.0;
+const CONNECTOR_ALIGNMENT_PROXIMITY: N = 7.0;
 
 const LANE_DISTANCE: N = 5.0;
 const CENTER_LANE_DISTANCE: N = 6.0;
+// cost per radian of direction change along the auto-routed path
+const TURN_PENALTY_FACTOR: N = 50.0;
+const MAX_TURN_PENALTY: N = 200.0;
 
 #[derive(Compact, Clone)]
 pub enum Intent {
     None,
     NewRoad(CVec<P2>),
     ContinueRoad(CVec<(LaneStrokeRef, ContinuationMode)>, CVec<P2>, P2),
+    AutoRoute(P2, P2),
     Select(SelectableStrokeRef, N, N),
     MaximizeSelection,
     MoveSelection(V2),
@@ -29,13 +40,14 @@ impl Default for Intent {
 }
 
 pub fn apply_intent(current: &PlanStep,
+                    intent: &Intent,
                     maybe_still_built_strokes: Option<&BuiltStrokes>,
                     settings: &Settings)
                     -> PlanStep {
 
     let still_built_strokes = || maybe_still_built_strokes.expect("still built strokes needed");
 
-    match current.intent {
+    match *intent {
         Intent::None => current.clone(),
 
         Intent::NewRoad(ref points) => apply_new_road(points, current, settings),
@@ -47,6 +59,10 @@ pub fn apply_intent(current: &PlanStep,
                                 current)
         }
 
+        Intent::AutoRoute(start, goal) => {
+            apply_auto_route(start, goal, current, still_built_strokes(), settings)
+        }
+
         Intent::Select(selection_ref, start, end) => {
             apply_select(selection_ref,
                          start,
@@ -184,6 +200,189 @@ fn apply_continue_road(continue_from: &CVec<(LaneStrokeRef, ContinuationMode)>,
     }
 }
 
+// A* search node on the visibility graph built from obstacle strokes.
+// `f = g + h` drives the priority queue; BinaryHeap is a max-heap, so
+// ordering is reversed to pop the lowest `f` first.
+struct RouteNode {
+    vertex: usize,
+    g: N,
+    f: N,
+}
+
+impl PartialEq for RouteNode {
+    fn eq(&self, other: &RouteNode) -> bool {
+        self.f == other.f
+    }
+}
+impl Eq for RouteNode {}
+
+impl PartialOrd for RouteNode {
+    fn partial_cmp(&self, other: &RouteNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RouteNode {
+    fn cmp(&self, other: &RouteNode) -> Ordering {
+        other.f.partial_cmp(&self.f).unwrap_or(Ordering::Equal)
+    }
+}
+
+fn auto_route_obstacles<'a>(current: &'a PlanStep,
+                            still_built_strokes: &'a BuiltStrokes)
+                            -> Vec<&'a LaneStroke> {
+    current.plan_delta
+        .new_strokes
+        .iter()
+        .chain(still_built_strokes.mapping.pairs().map(|(_, stroke)| stroke))
+        .collect()
+}
+
+// an edge is blocked if any point sampled along it comes within
+// `LANE_DISTANCE` of an existing stroke's centerline; `excluded_obstacles`
+// leaves out the stroke(s) that `from`/`to` themselves sit on, since a
+// vertex taken from a stroke's own nodes has distance 0 to it by
+// construction and would otherwise reject every edge touching that vertex
+fn auto_route_edge_blocked(from: P2,
+                           to: P2,
+                           obstacles: &[&LaneStroke],
+                           excluded_obstacles: &[usize])
+                           -> bool {
+    let edge_length = (to - from).norm();
+    if edge_length < ::descartes::MIN_START_TO_END {
+        return false;
+    }
+    let n_samples = (edge_length / LANE_DISTANCE).ceil().max(1.0) as usize;
+
+    for i in 0..(n_samples + 1) {
+        let t = i as N / n_samples as N;
+        let sample = from + (to - from) * t;
+
+        for (obstacle_idx, obstacle) in obstacles.iter().enumerate() {
+            if excluded_obstacles.contains(&obstacle_idx) {
+                continue;
+            }
+            if let Some(distance_along) = obstacle.path().project(sample) {
+                let closest_point = obstacle.path().along(distance_along);
+                if (closest_point - sample).norm() < LANE_DISTANCE {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+fn auto_route_turn_penalty(incoming_direction: Option<V2>, edge_direction: V2) -> N {
+    incoming_direction.map_or(0.0, |direction| {
+        let cos_angle = direction.dot(&edge_direction).min(1.0).max(-1.0);
+        (cos_angle.acos() * TURN_PENALTY_FACTOR).min(MAX_TURN_PENALTY)
+    })
+}
+
+// finds a path from `start` to `goal` over a graph whose vertices are
+// `start`, `goal` and the nodes of every existing stroke; an edge is kept
+// only if it stays `LANE_DISTANCE` away from every stroke other than the
+// ones its two endpoints were taken from, so waypoints can lie on existing
+// strokes. If no path is found, the road runs straight from `start` to
+// `goal` without any obstacle check. The waypoints are fed into
+// `apply_new_road` so the usual arc-fitting turns them into smooth lanes
+fn apply_auto_route(start: P2,
+                    goal: P2,
+                    current: &PlanStep,
+                    still_built_strokes: &BuiltStrokes,
+                    settings: &Settings)
+                    -> PlanStep {
+    let obstacles = auto_route_obstacles(current, still_built_strokes);
+
+    // `vertex_owners[i]` is the obstacle index a vertex was sampled from,
+    // or `None` for `start`/`goal`, so edges touching it can exclude that
+    // obstacle's own centerline from the collision check
+    let mut vertices = vec![start, goal];
+    let mut vertex_owners = vec![None, None];
+    for (obstacle_idx, obstacle) in obstacles.iter().enumerate() {
+        for node in obstacle.nodes() {
+            vertices.push(node.position);
+            vertex_owners.push(Some(obstacle_idx));
+        }
+    }
+    const START: usize = 0;
+    const GOAL: usize = 1;
+
+    let mut open = BinaryHeap::new();
+    let mut best_g = HashMap::new();
+    let mut came_from = HashMap::new();
+    let mut incoming_direction = HashMap::new();
+    let mut closed = HashSet::new();
+
+    best_g.insert(START, 0.0);
+    open.push(RouteNode { vertex: START, g: 0.0, f: (goal - start).norm() });
+
+    let mut found_goal = false;
+
+    while let Some(node) = open.pop() {
+        if closed.contains(&node.vertex) {
+            continue;
+        }
+        closed.insert(node.vertex);
+
+        if node.vertex == GOAL {
+            found_goal = true;
+            break;
+        }
+
+        let from = vertices[node.vertex];
+        let direction_in = incoming_direction.get(&node.vertex).cloned();
+
+        for other in 0..vertices.len() {
+            if other == node.vertex || closed.contains(&other) {
+                continue;
+            }
+            let to = vertices[other];
+            let excluded_obstacles = vertex_owners[node.vertex].into_iter()
+                .chain(vertex_owners[other])
+                .collect::<Vec<_>>();
+            if auto_route_edge_blocked(from, to, &obstacles, &excluded_obstacles) {
+                continue;
+            }
+
+            let edge_length = (to - from).norm();
+            let edge_direction = (to - from) / edge_length;
+            let tentative_g = node.g + edge_length +
+                              auto_route_turn_penalty(direction_in, edge_direction);
+
+            if tentative_g < *best_g.get(&other).unwrap_or(&::std::f32::MAX) {
+                best_g.insert(other, tentative_g);
+                came_from.insert(other, node.vertex);
+                incoming_direction.insert(other, edge_direction);
+                let h = (goal - to).norm();
+                open.push(RouteNode { vertex: other, g: tentative_g, f: tentative_g + h });
+            }
+        }
+    }
+
+    if !found_goal {
+        // no way around the obstacles was found; fall back to a direct road
+        let mut points = CVec::new();
+        points.push(start);
+        points.push(goal);
+        return apply_new_road(&points, current, settings);
+    }
+
+    let mut reversed_path = vec![vertices[GOAL]];
+    let mut at = GOAL;
+    while let Some(&prev) = came_from.get(&at) {
+        reversed_path.push(vertices[prev]);
+        at = prev;
+    }
+
+    let mut points = CVec::new();
+    points.extend(reversed_path.into_iter().rev());
+
+    apply_new_road(&points, current, settings)
+}
+
 fn apply_select(selection_ref: SelectableStrokeRef,
                 start: N,
                 end: N,
@@ -210,9 +409,21 @@ fn apply_select(selection_ref: SelectableStrokeRef,
             .map(|(new_idx, new_stroke)| (SelectableStrokeRef::New(new_idx), new_stroke))
             .chain(still_built_strokes.mapping
                 .pairs()
-                .map(|(old_ref, old_stroke)| (SelectableStrokeRef::Built(*old_ref), old_stroke)));
+                .map(|(old_ref, old_stroke)| (SelectableStrokeRef::Built(*old_ref), old_stroke)))
+            .collect::<Vec<_>>();
+
+        let stroke_index = RTree::bulk_load(all_strokes.iter()
+            .map(|&(other_ref, other_stroke)| {
+                (Aabb::from_points(other_stroke.nodes().iter().map(|node| node.position))
+                     .inflated(LANE_DISTANCE),
+                 (other_ref, other_stroke))
+            })
+            .collect());
+
+        let selection_box = Aabb::from_points(vec![start_position, end_position])
+            .inflated(SELECT_PARALLEL_PROXIMITY);
 
-        for (other_ref, other_stroke) in all_strokes {
+        for &(other_ref, other_stroke) in stroke_index.query(&selection_box) {
             if other_ref != selection_ref {
                 if let (Some(start_on_other_distance), Some(end_on_other_distance)) =
                     (other_stroke.path().project(start_position),
@@ -303,40 +514,73 @@ fn apply_move_selection(delta: V2,
         }
     }
 
-    for ((&ref_a,
-          &(_,
-            ref maybe_before_connector_a,
-            ref new_subsection_a,
-            ref maybe_after_connector_a,
-            _)),
-         (&ref_b,
-          &(_,
-            ref maybe_before_connector_b,
-            ref new_subsection_b,
-            ref maybe_after_connector_b,
-            _))) in
-        with_subsections_moved.iter()
-            .cartesian_product(with_subsections_moved.iter())
-            .filter(|&((a, _), (b, _))| a != b) {
-        if a_close_and_right_of_b(new_subsection_a.get(0), new_subsection_b.get(0)) &&
-           maybe_before_connector_a.is_some() && maybe_before_connector_b.is_some() {
-            connector_alignments.push(((ref_a, C::Before), (ref_b, C::Before)));
-        }
-        if a_close_and_right_of_b(new_subsection_a.get(0), new_subsection_b.last()) &&
-           maybe_before_connector_a.is_some() && maybe_after_connector_b.is_some() &&
-           !connector_alignments.iter()
-            .any(|other| other == &((ref_b, C::After), (ref_a, C::Before))) {
-            connector_alignments.push(((ref_a, C::Before), (ref_b, C::After)));
-        }
-        if a_close_and_right_of_b(new_subsection_a.last(), new_subsection_b.last()) &&
-           maybe_after_connector_a.is_some() && maybe_after_connector_b.is_some() {
-            connector_alignments.push(((ref_a, C::After), (ref_b, C::After)));
+    // index both subsection endpoints of every selection, so for a given
+    // selection we only need to test the handful of others whose endpoints
+    // actually fall within the alignment radius, instead of every pair
+    let endpoint_index = RTree::bulk_load(with_subsections_moved.iter()
+        .flat_map(|(&ref_b, &(_, _, ref new_subsection_b, _, _))| {
+            new_subsection_b.get(0)
+                .into_iter()
+                .chain(new_subsection_b.last())
+                .map(|node| {
+                    (Aabb::from_points(vec![node.position]).inflated(CONNECTOR_ALIGNMENT_PROXIMITY),
+                     ref_b)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect());
+
+    // `candidate_refs` below is collected in a std `HashSet`, whose
+    // iteration order varies from run to run; since the anti-duplicate
+    // guards further down only keep whichever of a pair's two symmetric
+    // orderings is pushed first, that order decides which stroke is moved.
+    // Sorting both loops by `SelectableStrokeRef` makes the choice the same
+    // for the same selection. This order is not the `FnvHashMap` order the
+    // old all-pairs loop followed, so the choice can differ from that loop's.
+    let mut ordered_refs = with_subsections_moved.keys().cloned().collect::<Vec<_>>();
+    ordered_refs.sort();
+
+    for &ref_a in &ordered_refs {
+        let &(_, ref maybe_before_connector_a, ref new_subsection_a, ref maybe_after_connector_a, _) =
+            &with_subsections_moved[&ref_a];
+        let mut candidate_refs = HashSet::new();
+        for endpoint in new_subsection_a.get(0).into_iter().chain(new_subsection_a.last()) {
+            let query_box = Aabb::from_points(vec![endpoint.position])
+                .inflated(CONNECTOR_ALIGNMENT_PROXIMITY);
+            for &candidate_ref in endpoint_index.query(&query_box) {
+                candidate_refs.insert(candidate_ref);
+            }
         }
-        if a_close_and_right_of_b(new_subsection_a.last(), new_subsection_b.get(0)) &&
-           maybe_after_connector_a.is_some() && maybe_before_connector_b.is_some() &&
-           !connector_alignments.iter()
-            .any(|other| other == &((ref_b, C::Before), (ref_a, C::After))) {
-            connector_alignments.push(((ref_a, C::After), (ref_b, C::Before)));
+        let mut candidate_refs = candidate_refs.into_iter().collect::<Vec<_>>();
+        candidate_refs.sort();
+
+        for ref_b in candidate_refs {
+            if ref_b == ref_a {
+                continue;
+            }
+            let &(_, ref maybe_before_connector_b, ref new_subsection_b, ref maybe_after_connector_b, _) =
+                &with_subsections_moved[&ref_b];
+
+            if a_close_and_right_of_b(new_subsection_a.get(0), new_subsection_b.get(0)) &&
+               maybe_before_connector_a.is_some() && maybe_before_connector_b.is_some() {
+                connector_alignments.push(((ref_a, C::Before), (ref_b, C::Before)));
+            }
+            if a_close_and_right_of_b(new_subsection_a.get(0), new_subsection_b.last()) &&
+               maybe_before_connector_a.is_some() && maybe_after_connector_b.is_some() &&
+               !connector_alignments.iter()
+                .any(|other| other == &((ref_b, C::After), (ref_a, C::Before))) {
+                connector_alignments.push(((ref_a, C::Before), (ref_b, C::After)));
+            }
+            if a_close_and_right_of_b(new_subsection_a.last(), new_subsection_b.last()) &&
+               maybe_after_connector_a.is_some() && maybe_after_connector_b.is_some() {
+                connector_alignments.push(((ref_a, C::After), (ref_b, C::After)));
+            }
+            if a_close_and_right_of_b(new_subsection_a.last(), new_subsection_b.get(0)) &&
+               maybe_after_connector_a.is_some() && maybe_before_connector_b.is_some() &&
+               !connector_alignments.iter()
+                .any(|other| other == &((ref_b, C::Before), (ref_a, C::After))) {
+                connector_alignments.push(((ref_a, C::After), (ref_b, C::Before)));
+            }
         }
     }
 
@@ -495,4 +739,45 @@ fn apply_create_next_lane(current: &PlanStep, still_built_strokes: &BuiltStrokes
         selections: CDict::new(),
         intent: Intent::None,
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_penalty_is_zero_without_an_incoming_direction() {
+        assert_eq!(auto_route_turn_penalty(None, V2::new(1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn turn_penalty_grows_with_sharper_turns_and_stays_capped() {
+        let straight = auto_route_turn_penalty(Some(V2::new(1.0, 0.0)), V2::new(1.0, 0.0));
+        let slight_turn = auto_route_turn_penalty(Some(V2::new(1.0, 0.0)), V2::new(0.0, 1.0));
+        let u_turn = auto_route_turn_penalty(Some(V2::new(1.0, 0.0)), V2::new(-1.0, 0.0));
+
+        assert!(straight < slight_turn);
+        assert!(slight_turn < u_turn);
+        assert!(u_turn <= MAX_TURN_PENALTY);
+    }
+
+    #[test]
+    fn route_node_ordering_pops_lowest_f_first() {
+        let mut heap = BinaryHeap::new();
+        heap.push(RouteNode { vertex: 0, g: 0.0, f: 10.0 });
+        heap.push(RouteNode { vertex: 1, g: 0.0, f: 1.0 });
+        heap.push(RouteNode { vertex: 2, g: 0.0, f: 5.0 });
+
+        assert_eq!(heap.pop().unwrap().vertex, 1);
+        assert_eq!(heap.pop().unwrap().vertex, 2);
+        assert_eq!(heap.pop().unwrap().vertex, 0);
+    }
+
+    #[test]
+    fn edge_blocked_is_false_with_no_obstacles_to_check() {
+        // with no obstacles nothing can block the edge; the exclusion of a
+        // vertex's own stroke, whose index `apply_auto_route` passes in
+        // `excluded_obstacles`, is not exercised here
+        assert!(!auto_route_edge_blocked(P2::new(0.0, 0.0), P2::new(10.0, 0.0), &[], &[]));
+    }
 }
\ No newline at end of file
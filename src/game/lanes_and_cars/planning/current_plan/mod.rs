@@ -0,0 +1,9 @@
+// `PlanStep`, `Settings`, `LaneStrokeRef`, `SelectableStrokeRef` and
+// `ContinuationMode` normally live directly in this module; they're part of
+// the wider planning module this snapshot doesn't include, so only the
+// pieces touched by this chunk series are declared here.
+pub mod intent;
+pub mod history;
+
+pub use self::intent::{Intent, apply_intent};
+pub use self::history::PlanHistory;
@@ -0,0 +1,90 @@
+use compact::CVec;
+use descartes::{N, P2, Norm, WithUniqueOrthogonal, Dot};
+
+use super::lane_stroke::{LaneStroke, LaneStrokeNode};
+use super::plan::PlanDelta;
+
+impl LaneStroke {
+    /// Offsets the centerline by `half_width` to either side and joins the
+    /// two sides into one closed polygon, mitered at convex corners.
+    pub fn to_fill_outline(&self, half_width: N) -> CVec<P2> {
+        let nodes = self.nodes();
+        let mut left_side = offset_side(nodes, 1.0, half_width);
+        let right_side = offset_side(nodes, -1.0, half_width);
+
+        let mut outline = CVec::new();
+        outline.extend(left_side.drain(..));
+        outline.extend(right_side.into_iter().rev());
+        outline
+    }
+}
+
+/// Runs `to_fill_outline` over every stroke in `delta.new_strokes`;
+/// `strokes_to_destroy` is not included.
+pub fn plan_to_fill_outlines(delta: &PlanDelta, half_width: N) -> CVec<CVec<P2>> {
+    delta.new_strokes.iter().map(|stroke| stroke.to_fill_outline(half_width)).collect()
+}
+
+fn offset_side(nodes: &[LaneStrokeNode], side_sign: N, half_width: N) -> Vec<P2> {
+    let mut side = Vec::with_capacity(nodes.len());
+
+    for (i, node) in nodes.iter().enumerate() {
+        let offset = node.direction.orthogonal() * half_width * side_sign;
+        side.push(node.position + offset);
+
+        if let Some(next) = nodes.get(i + 1) {
+            if is_convex_corner(node, next, side_sign) {
+                side.push(miter_point(node, next, half_width, side_sign));
+            }
+        }
+    }
+
+    side
+}
+
+// a corner is convex on this side when the path turns away from it, i.e.
+// the incoming-to-outgoing direction change points away from this side
+fn is_convex_corner(node: &LaneStrokeNode, next: &LaneStrokeNode, side_sign: N) -> bool {
+    (next.direction - node.direction).dot(&(node.direction.orthogonal() * side_sign)) < 0.0
+}
+
+// bisects the two adjacent tangents to get a simple miter join point on the
+// outer side of the corner, between the two per-node offsets
+fn miter_point(node: &LaneStrokeNode, next: &LaneStrokeNode, half_width: N, side_sign: N) -> P2 {
+    let bisector_direction = (node.direction + next.direction).normalize();
+    next.position + bisector_direction.orthogonal() * half_width * side_sign
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use descartes::{V2, RoughlyComparable};
+
+    fn node(x: N, y: N, direction: V2) -> LaneStrokeNode {
+        LaneStrokeNode {
+            position: P2::new(x, y),
+            direction: direction,
+        }
+    }
+
+    #[test]
+    fn is_convex_corner_is_true_on_the_outside_of_a_left_turn() {
+        let before = node(0.0, 0.0, V2::new(1.0, 0.0));
+        let after = node(1.0, 0.0, V2::new(0.0, 1.0));
+
+        assert!(is_convex_corner(&before, &after, 1.0));
+        assert!(!is_convex_corner(&before, &after, -1.0));
+    }
+
+    #[test]
+    fn miter_point_sits_on_the_bisector_of_the_two_tangents() {
+        let before = node(0.0, 0.0, V2::new(1.0, 0.0));
+        let after = node(1.0, 0.0, V2::new(0.0, 1.0));
+
+        let point = miter_point(&before, &after, 1.0, 1.0);
+        let bisector = (before.direction + after.direction).normalize().orthogonal();
+        let expected = after.position + bisector;
+
+        assert!(point.is_roughly_within(&expected, 0.001));
+    }
+}
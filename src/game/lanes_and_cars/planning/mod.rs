@@ -0,0 +1,7 @@
+// `plan` and `lane_stroke` are part of the wider planning module this
+// snapshot doesn't include; only the pieces touched by this chunk series
+// are declared here.
+pub mod current_plan;
+pub mod svg;
+pub mod lane_stroke_fill;
+pub mod spatial_index;
@@ -0,0 +1,199 @@
+use descartes::{N, P2};
+
+/// An axis-aligned bounding box, used as the key for `RTree` entries.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: P2,
+    pub max: P2,
+}
+
+impl Aabb {
+    pub fn from_points<I: IntoIterator<Item = P2>>(points: I) -> Aabb {
+        let mut points = points.into_iter();
+        let first = points.next().expect("Aabb needs at least one point");
+        points.fold(Aabb { min: first, max: first }, |aabb, point| aabb.extended(point))
+    }
+
+    fn extended(&self, point: P2) -> Aabb {
+        Aabb {
+            min: P2::new(self.min.x.min(point.x), self.min.y.min(point.y)),
+            max: P2::new(self.max.x.max(point.x), self.max.y.max(point.y)),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: P2::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: P2::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    pub fn inflated(&self, amount: N) -> Aabb {
+        Aabb {
+            min: P2::new(self.min.x - amount, self.min.y - amount),
+            max: P2::new(self.max.x + amount, self.max.y + amount),
+        }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x && self.min.y <= other.max.y &&
+        self.max.y >= other.min.y
+    }
+}
+
+const NODE_CAPACITY: usize = 8;
+
+enum Node<T> {
+    Leaf(Aabb, Vec<(Aabb, T)>),
+    Branch(Aabb, Vec<Node<T>>),
+}
+
+fn bbox_of<T>(node: &Node<T>) -> Aabb {
+    match *node {
+        Node::Leaf(bbox, _) | Node::Branch(bbox, _) => bbox,
+    }
+}
+
+/// A static R-tree, bulk-loaded once and queried for boxes that might
+/// intersect a given region.
+pub struct RTree<T> {
+    root: Option<Node<T>>,
+}
+
+impl<T> RTree<T> {
+    /// Bulk-loads an R-tree via a sort-tile-recursive layout.
+    pub fn bulk_load(mut entries: Vec<(Aabb, T)>) -> RTree<T> {
+        if entries.is_empty() {
+            return RTree { root: None };
+        }
+
+        entries.sort_by(|a, b| a.0.min.x.partial_cmp(&b.0.min.x).unwrap());
+
+        let n_leaves = (entries.len() + NODE_CAPACITY - 1) / NODE_CAPACITY;
+        let slice_count = (n_leaves as f32).sqrt().ceil().max(1.0) as usize;
+        let slice_size = ((entries.len() + slice_count - 1) / slice_count).max(NODE_CAPACITY);
+
+        let mut leaves = Vec::new();
+        let mut remaining = entries;
+
+        while !remaining.is_empty() {
+            let slice_len = slice_size.min(remaining.len());
+            let mut slice = remaining.drain(0..slice_len).collect::<Vec<_>>();
+            slice.sort_by(|a, b| a.0.min.y.partial_cmp(&b.0.min.y).unwrap());
+
+            while !slice.is_empty() {
+                let leaf_len = NODE_CAPACITY.min(slice.len());
+                let leaf_entries = slice.drain(0..leaf_len).collect::<Vec<_>>();
+                let bbox = leaf_entries.iter()
+                    .skip(1)
+                    .fold(leaf_entries[0].0, |acc, &(bbox, _)| acc.union(&bbox));
+                leaves.push(Node::Leaf(bbox, leaf_entries));
+            }
+        }
+
+        RTree { root: Some(Self::build_levels(leaves)) }
+    }
+
+    fn build_levels(nodes: Vec<Node<T>>) -> Node<T> {
+        let mut current = nodes;
+
+        while current.len() > 1 {
+            let mut next = Vec::new();
+            let mut remaining = current.into_iter();
+
+            loop {
+                let group = remaining.by_ref().take(NODE_CAPACITY).collect::<Vec<_>>();
+                if group.is_empty() {
+                    break;
+                }
+                let bbox = group.iter().skip(1).fold(bbox_of(&group[0]), |acc, node| acc.union(&bbox_of(node)));
+                next.push(Node::Branch(bbox, group));
+            }
+
+            current = next;
+        }
+
+        current.into_iter().next().expect("build_levels needs at least one node")
+    }
+
+    /// Returns every entry whose bounding box intersects `query_box`.
+    pub fn query(&self, query_box: &Aabb) -> Vec<&T> {
+        let mut out = Vec::new();
+        if let Some(ref root) = self.root {
+            Self::query_node(root, query_box, &mut out);
+        }
+        out
+    }
+
+    fn query_node<'a>(node: &'a Node<T>, query_box: &Aabb, out: &mut Vec<&'a T>) {
+        match *node {
+            Node::Leaf(bbox, ref entries) => {
+                if bbox.intersects(query_box) {
+                    for &(ref entry_box, ref value) in entries {
+                        if entry_box.intersects(query_box) {
+                            out.push(value);
+                        }
+                    }
+                }
+            }
+            Node::Branch(bbox, ref children) => {
+                if bbox.intersects(query_box) {
+                    for child in children {
+                        Self::query_node(child, query_box, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aabb_intersects_is_false_for_boxes_separated_on_either_axis() {
+        let a = Aabb { min: P2::new(0.0, 0.0), max: P2::new(1.0, 1.0) };
+        let separated_on_x = Aabb { min: P2::new(2.0, 0.0), max: P2::new(3.0, 1.0) };
+        let separated_on_y = Aabb { min: P2::new(0.0, 2.0), max: P2::new(1.0, 3.0) };
+        let touching = Aabb { min: P2::new(1.0, 1.0), max: P2::new(2.0, 2.0) };
+
+        assert!(!a.intersects(&separated_on_x));
+        assert!(!a.intersects(&separated_on_y));
+        assert!(a.intersects(&touching));
+    }
+
+    #[test]
+    fn aabb_inflated_grows_every_side_by_the_given_amount() {
+        let a = Aabb { min: P2::new(0.0, 0.0), max: P2::new(1.0, 1.0) };
+        let grown = a.inflated(2.0);
+
+        assert_eq!(grown.min, P2::new(-2.0, -2.0));
+        assert_eq!(grown.max, P2::new(3.0, 3.0));
+    }
+
+    #[test]
+    fn rtree_query_finds_only_entries_whose_box_intersects_the_query() {
+        let entries = (0..50)
+            .map(|i| {
+                let bbox = Aabb::from_points(vec![P2::new(i as N, i as N)]);
+                (bbox, i)
+            })
+            .collect::<Vec<_>>();
+        let tree = RTree::bulk_load(entries);
+
+        let query_box = Aabb::from_points(vec![P2::new(10.0, 10.0)]).inflated(0.5);
+        let mut found = tree.query(&query_box).into_iter().cloned().collect::<Vec<_>>();
+        found.sort();
+
+        assert_eq!(found, vec![10]);
+    }
+
+    #[test]
+    fn rtree_query_on_an_empty_tree_returns_nothing() {
+        let tree = RTree::<usize>::bulk_load(Vec::new());
+        let query_box = Aabb::from_points(vec![P2::new(0.0, 0.0)]).inflated(1.0);
+
+        assert!(tree.query(&query_box).is_empty());
+    }
+}
@@ -0,0 +1,328 @@
+use compact::CVec;
+use descartes::{N, P2, Norm, RoughlyComparable};
+
+use super::plan::PlanDelta;
+use super::lane_stroke::LaneStroke;
+use super::current_plan::Intent;
+
+// default tolerance (in scene units) used when flattening imported curves
+const DEFAULT_FLATTENING_TOLERANCE: N = 0.5;
+
+/// Renders every `LaneStroke` in `delta.new_strokes` as an SVG `<path>` along
+/// its centerline; `strokes_to_destroy` is not included.
+pub fn plan_to_svg(delta: &PlanDelta) -> String {
+    let paths = delta.new_strokes
+        .iter()
+        .map(|stroke| format!("  <path d=\"{}\" fill=\"none\" stroke=\"black\"/>", stroke_to_path_d(stroke)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\">\n{}\n</svg>", paths)
+}
+
+fn stroke_to_path_d(stroke: &LaneStroke) -> String {
+    let nodes = stroke.nodes();
+    let mut d = format!("M {} {}", nodes[0].position.x, nodes[0].position.y);
+
+    for window in nodes.windows(2) {
+        let (from, to) = (window[0], window[1]);
+        // place control handles along each node's tangent, a third of the
+        // way to the next node, which approximates the arcs used on import
+        let handle_length = (to.position - from.position).norm() / 3.0;
+        let control_1 = from.position + from.direction * handle_length;
+        let control_2 = to.position - to.direction * handle_length;
+
+        d.push_str(&format!(" C {} {}, {} {}, {} {}",
+                            control_1.x,
+                            control_1.y,
+                            control_2.x,
+                            control_2.y,
+                            to.position.x,
+                            to.position.y));
+    }
+
+    d
+}
+
+/// Parses an SVG `d` attribute into an `Intent::NewRoad`. Supports absolute
+/// and relative `M`/`L`/`C`/`Q` and `Z`; arcs and smooth-curve shorthands are
+/// skipped. Returns `None` if fewer than two points come out of it.
+pub fn svg_to_intent(svg_path: &str) -> Option<Intent> {
+    let segments = parse_path_d(svg_path);
+    let mut points = CVec::new();
+
+    for segment in segments {
+        match segment {
+            PathSegment::MoveTo(point) => points.push(point),
+            PathSegment::CubicTo(control_1, control_2, end) => {
+                if let Some(&start) = points.last() {
+                    flatten_cubic(start,
+                                 control_1,
+                                 control_2,
+                                 end,
+                                 DEFAULT_FLATTENING_TOLERANCE,
+                                 &mut points);
+                }
+            }
+            PathSegment::QuadTo(control, end) => {
+                if let Some(&start) = points.last() {
+                    let control_1 = start + (control - start) * (2.0 / 3.0);
+                    let control_2 = end + (control - end) * (2.0 / 3.0);
+                    flatten_cubic(start, control_1, control_2, end, DEFAULT_FLATTENING_TOLERANCE, &mut points);
+                }
+            }
+        }
+    }
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    Some(Intent::NewRoad(points))
+}
+
+enum PathSegment {
+    MoveTo(P2),
+    CubicTo(P2, P2, P2),
+    QuadTo(P2, P2),
+}
+
+fn parse_path_d(d: &str) -> Vec<PathSegment> {
+    let tokens = d.replace(',', " ");
+    let mut numbers = tokens.split_whitespace().peekable();
+    let mut segments = Vec::new();
+    let mut command = ' ';
+    let mut current_point = P2::new(0.0, 0.0);
+    let mut subpath_start = P2::new(0.0, 0.0);
+
+    loop {
+        let token = match numbers.peek() {
+            Some(token) => *token,
+            None => break,
+        };
+
+        if let Some(first_char) = token.chars().next() {
+            if first_char.is_alphabetic() {
+                command = first_char;
+                numbers.next();
+
+                if command == 'Z' || command == 'z' {
+                    // closepath: draw back to the start of the current subpath
+                    segments.push(PathSegment::MoveTo(subpath_start));
+                    current_point = subpath_start;
+                }
+                continue;
+            }
+        }
+
+        // lowercase SVG commands are relative to the current point
+        let is_relative = command.is_lowercase();
+
+        // a missing or unparseable coordinate misaligns every command after
+        // it, so bail out of the whole path rather than guess 0.0 and snap
+        // a point to the origin
+        match command.to_ascii_uppercase() {
+            'M' => {
+                let point = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                segments.push(PathSegment::MoveTo(point));
+                current_point = point;
+                subpath_start = point;
+            }
+            'L' => {
+                let point = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                segments.push(PathSegment::MoveTo(point));
+                current_point = point;
+            }
+            'C' => {
+                let control_1 = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                let control_2 = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                let end = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                segments.push(PathSegment::CubicTo(control_1, control_2, end));
+                current_point = end;
+            }
+            'Q' => {
+                let control = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                let end = match next_point(&mut numbers, current_point, is_relative) {
+                    Some(point) => point,
+                    None => break,
+                };
+                segments.push(PathSegment::QuadTo(control, end));
+                current_point = end;
+            }
+            _ => {
+                // unsupported command (e.g. arcs, smooth-curve shorthand) -
+                // skip its numbers one at a time so the token stream stays
+                // aligned for the next recognized command
+                numbers.next();
+            }
+        }
+    }
+
+    segments
+}
+
+fn next_point<'a, I: Iterator<Item = &'a str>>(numbers: &mut I,
+                                               relative_to: P2,
+                                               is_relative: bool)
+                                               -> Option<P2> {
+    let maybe_x = numbers.next().and_then(|n| n.parse::<N>().ok());
+    let maybe_y = numbers.next().and_then(|n| n.parse::<N>().ok());
+
+    match (maybe_x, maybe_y) {
+        (Some(x), Some(y)) if is_relative => Some(P2::new(relative_to.x + x, relative_to.y + y)),
+        (Some(x), Some(y)) => Some(P2::new(x, y)),
+        _ => None,
+    }
+}
+
+// recursively subdivides a cubic Bézier until its control points are within
+// `tolerance` of the chord, then appends the resulting polyline vertices
+fn flatten_cubic(start: P2, control_1: P2, control_2: P2, end: P2, tolerance: N, out: &mut CVec<P2>) {
+    if cubic_flatness(start, control_1, control_2, end) <= tolerance {
+        out.push(end);
+        return;
+    }
+
+    let (left, right) = subdivide_cubic(start, control_1, control_2, end);
+    flatten_cubic(left.0, left.1, left.2, left.3, tolerance, out);
+    flatten_cubic(right.0, right.1, right.2, right.3, tolerance, out);
+}
+
+// maximum distance of either control point from the start-end chord
+fn cubic_flatness(start: P2, control_1: P2, control_2: P2, end: P2) -> N {
+    distance_to_chord(control_1, start, end).max(distance_to_chord(control_2, start, end))
+}
+
+fn distance_to_chord(point: P2, chord_start: P2, chord_end: P2) -> N {
+    let chord = chord_end - chord_start;
+    let chord_length = chord.norm();
+    if chord_length < 1e-6 {
+        return (point - chord_start).norm();
+    }
+    let offset = point - chord_start;
+    (offset.x * chord.y - offset.y * chord.x).abs() / chord_length
+}
+
+type CubicPoints = (P2, P2, P2, P2);
+
+fn subdivide_cubic(start: P2, control_1: P2, control_2: P2, end: P2) -> (CubicPoints, CubicPoints) {
+    let mid_1 = midpoint(start, control_1);
+    let mid_2 = midpoint(control_1, control_2);
+    let mid_3 = midpoint(control_2, end);
+    let mid_12 = midpoint(mid_1, mid_2);
+    let mid_23 = midpoint(mid_2, mid_3);
+    let mid_123 = midpoint(mid_12, mid_23);
+
+    ((start, mid_1, mid_12, mid_123), (mid_123, mid_23, mid_3, end))
+}
+
+fn midpoint(a: P2, b: P2) -> P2 {
+    P2::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten_cubic_collapses_a_straight_control_polygon_to_just_the_end_point() {
+        let mut out = CVec::new();
+        flatten_cubic(P2::new(0.0, 0.0),
+                      P2::new(1.0, 0.0),
+                      P2::new(2.0, 0.0),
+                      P2::new(3.0, 0.0),
+                      0.5,
+                      &mut out);
+
+        assert_eq!(out.len(), 1);
+        assert!(out[0].is_roughly_within(&P2::new(3.0, 0.0), 0.001));
+    }
+
+    #[test]
+    fn flatten_cubic_subdivides_a_sharp_bow_until_within_tolerance() {
+        let mut out = CVec::new();
+        flatten_cubic(P2::new(0.0, 0.0),
+                      P2::new(1.0, 10.0),
+                      P2::new(2.0, -10.0),
+                      P2::new(3.0, 0.0),
+                      0.5,
+                      &mut out);
+
+        assert!(out.len() > 1);
+        assert!(out.last().unwrap().is_roughly_within(&P2::new(3.0, 0.0), 0.001));
+    }
+
+    #[test]
+    fn parse_path_d_resolves_relative_lowercase_commands_against_the_current_point() {
+        let intent = svg_to_intent("M 10 10 l 5 0 l 0 5").expect("should produce a road");
+
+        match intent {
+            Intent::NewRoad(points) => {
+                assert_eq!(points.len(), 3);
+                assert!(points[0].is_roughly_within(&P2::new(10.0, 10.0), 0.001));
+                assert!(points[1].is_roughly_within(&P2::new(15.0, 10.0), 0.001));
+                assert!(points[2].is_roughly_within(&P2::new(15.0, 15.0), 0.001));
+            }
+            _ => panic!("expected Intent::NewRoad"),
+        }
+    }
+
+    #[test]
+    fn parse_path_d_closepath_returns_to_the_subpath_start() {
+        let intent = svg_to_intent("M 1 1 L 5 1 L 5 5 Z").expect("should produce a road");
+
+        match intent {
+            Intent::NewRoad(points) => {
+                assert_eq!(points.len(), 4);
+                assert!(points[3].is_roughly_within(&P2::new(1.0, 1.0), 0.001));
+            }
+            _ => panic!("expected Intent::NewRoad"),
+        }
+    }
+
+    #[test]
+    fn svg_to_intent_is_none_for_a_curve_with_no_preceding_moveto() {
+        assert!(svg_to_intent("C 1 1, 2 2, 3 3").is_none());
+    }
+
+    #[test]
+    fn svg_to_intent_is_none_for_a_lone_moveto() {
+        assert!(svg_to_intent("M 1 1").is_none());
+    }
+
+    #[test]
+    fn svg_to_intent_is_none_for_an_empty_path() {
+        assert!(svg_to_intent("").is_none());
+    }
+
+    #[test]
+    fn parse_path_d_stops_at_a_corrupt_coordinate_instead_of_defaulting_to_zero() {
+        let intent = svg_to_intent("M 1 1 L 5 1 L not_a_number 9").expect("should produce a road");
+
+        match intent {
+            Intent::NewRoad(points) => {
+                assert_eq!(points.len(), 2);
+                assert!(points[1].is_roughly_within(&P2::new(5.0, 1.0), 0.001));
+            }
+            _ => panic!("expected Intent::NewRoad"),
+        }
+    }
+}